@@ -0,0 +1,482 @@
+//! A named-failpoint registry, loosely modeled on the `fail` crate.
+//!
+//! Unlike the blunt, global [`FAULT_INJECT_COUNTER`](crate::FAULT_INJECT_COUNTER),
+//! a failpoint is identified by a static string label (usually something like
+//! `"wal::fsync"`) and can be configured independently of every other call site
+//! via [`configure`]. This lets a test enable faults only at the specific
+//! subsystem it's exercising, instead of flipping a single crate-wide knob.
+//!
+//! Configuration is done with a small grammar, inspired by the one used by
+//! the `fail` crate:
+//!
+//! ```text
+//! spec := segment ("->" segment)*
+//! segment := [ probability "%" ] action [ "*" count ]
+//! action := "off" | "panic" | "print" | "delay(" millis ")"
+//!         | "return" [ "(" error_kind ")" ]
+//!         | "fatal" [ "(" error_kind ")" ]
+//! error_kind := "other" | "interrupted" | "would_block"
+//!             | "permission_denied" | "timed_out" | "unexpected_eof"
+//! ```
+//!
+//! Segments are chained: the first action fires (subject to its probability)
+//! until its count is exhausted, then the next segment takes over. A segment
+//! with no count fires forever. For example `"50%return->off"` means "inject
+//! an error about half the time, forever" while `"return*3->off"` means
+//! "inject an error on the next 3 hits, then stop injecting."
+//!
+//! `return` and `fatal` both inject an `io::Error` of the given `ErrorKind`
+//! (defaulting to `Other`), differing only in severity: a `return` action is
+//! soft/retryable and keeps firing according to its count like any other
+//! action, while a `fatal` action always fires exactly once and then
+//! advances to the next segment regardless of its configured count, to model
+//! an unrecoverable failure.
+
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::sync::{Mutex, OnceLock, RwLock};
+
+/// A single action in a failpoint's chain, optionally gated by a probability
+/// and a fire count before moving on to the next action in the chain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Action {
+    /// Probability in `[0.0, 1.0]` that this action fires when reached.
+    /// When it doesn't fire, the call site passes through untouched.
+    pub probability: f32,
+    /// What to do when this action fires.
+    pub kind: ActionKind,
+    /// How many times this action may fire before the chain advances to
+    /// the next action. `None` means it fires forever.
+    pub count: Option<u64>,
+}
+
+/// The kind of disruption a fired [`Action`] causes at its call site.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ActionKind {
+    /// Do nothing; let the call through.
+    Off,
+    /// Inject an `io::Error` in place of the call's real result.
+    Return(ReturnSpec),
+    /// Panic immediately.
+    Panic,
+    /// Sleep for the given number of milliseconds, then let the call through.
+    Delay(u64),
+    /// Print a notice that the failpoint fired, then let the call through.
+    Print,
+}
+
+/// Describes the `io::Error` injected by a [`ActionKind::Return`] action.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReturnSpec {
+    /// The `ErrorKind` the injected error carries, so that retry logic
+    /// keyed off e.g. `Interrupted`/`WouldBlock` can be exercised.
+    pub kind: ErrorKind,
+    /// Whether this action keeps firing according to its configured count
+    /// ([`Severity::Retryable`]), or always fires exactly once before the
+    /// chain advances ([`Severity::Fatal`]).
+    pub severity: Severity,
+}
+
+/// The severity of an injected error, following the RocksDB `db_stress`
+/// convention of soft/retryable vs. fatal errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Soft: the application is expected to retry, so this action may keep
+    /// injecting errors for as long as its count allows.
+    Retryable,
+    /// Fatal: models an unrecoverable failure, so this action always fires
+    /// exactly once regardless of its configured count.
+    Fatal,
+}
+
+/// What a fired (or passed-through) failpoint asks the `maybe!`/`fallible!`
+/// call site to do.
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Pass,
+    Return(ErrorKind),
+    Delay(u64),
+}
+
+#[derive(Debug)]
+struct FailpointState {
+    actions: Vec<Action>,
+    cursor: usize,
+    remaining: Option<u64>,
+}
+
+impl FailpointState {
+    fn new(actions: Vec<Action>) -> FailpointState {
+        let remaining = actions.first().and_then(|a| a.count);
+        FailpointState {
+            actions,
+            cursor: 0,
+            remaining,
+        }
+    }
+
+    fn current(&self) -> Action {
+        self.actions.get(self.cursor).copied().unwrap_or(Action {
+            probability: 1.0,
+            kind: ActionKind::Off,
+            count: None,
+        })
+    }
+
+    fn advance(&mut self) {
+        let Some(remaining) = self.remaining else {
+            return;
+        };
+
+        if remaining > 1 {
+            self.remaining = Some(remaining - 1);
+        } else {
+            self.advance_to_next_segment();
+        }
+    }
+
+    fn advance_to_next_segment(&mut self) {
+        if self.cursor + 1 < self.actions.len() {
+            self.cursor += 1;
+            self.remaining = self.actions[self.cursor].count;
+        } else {
+            // Past the last segment, `current` yields `Off` from now on.
+            self.cursor = self.actions.len();
+            self.remaining = None;
+        }
+    }
+
+    fn fire(&mut self) -> Outcome {
+        let action = self.current();
+
+        // Consult probability before advancing: `count` tracks actual
+        // fires, not evaluations, so a segment like `"50%return*4->off"`
+        // takes roughly 8 evaluations (~4 fires) to exhaust, not 4.
+        if action.probability < 1.0 && random_unit() >= action.probability {
+            return Outcome::Pass;
+        }
+
+        // A fatal action always fires exactly once, no matter what count
+        // it was configured with.
+        let is_fatal = matches!(
+            action.kind,
+            ActionKind::Return(ReturnSpec {
+                severity: Severity::Fatal,
+                ..
+            })
+        );
+        if is_fatal {
+            self.advance_to_next_segment();
+        } else {
+            self.advance();
+        }
+
+        match action.kind {
+            ActionKind::Off => Outcome::Pass,
+            ActionKind::Return(ReturnSpec { kind, .. }) => Outcome::Return(kind),
+            ActionKind::Panic => panic!("fault_injection: failpoint panic action fired"),
+            ActionKind::Delay(ms) => Outcome::Delay(ms),
+            ActionKind::Print => {
+                println!("fault_injection: failpoint fired");
+                Outcome::Pass
+            }
+        }
+    }
+}
+
+fn registry() -> &'static RwLock<HashMap<&'static str, Mutex<FailpointState>>> {
+    static REGISTRY: OnceLock<RwLock<HashMap<&'static str, Mutex<FailpointState>>>> =
+        OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// Configures the failpoint named `label` to behave according to `spec`,
+/// overwriting any previous configuration for that label.
+///
+/// See the [module docs](self) for the grammar accepted by `spec`.
+///
+/// # Errors
+/// Returns a human-readable message if `spec` is not a valid action chain.
+pub fn configure(label: &'static str, spec: &str) -> Result<(), String> {
+    let actions = parse_spec(spec)?;
+    registry()
+        .write()
+        .unwrap()
+        .insert(label, Mutex::new(FailpointState::new(actions)));
+    Ok(())
+}
+
+/// Removes any configuration for `label`, reverting its call sites to the
+/// fallback aggregate counter.
+pub fn clear(label: &'static str) {
+    registry().write().unwrap().remove(label);
+}
+
+/// Evaluates the failpoint named `label`, returning `None` if it has not
+/// been [`configure`]d, in which case the caller should fall back to the
+/// aggregate [`FAULT_INJECT_COUNTER`](crate::FAULT_INJECT_COUNTER).
+#[doc(hidden)]
+pub fn evaluate(label: &'static str) -> Option<Outcome> {
+    let registered = registry().read().unwrap();
+    registered
+        .get(label)
+        .map(|state| state.lock().unwrap().fire())
+}
+
+fn random_unit() -> f32 {
+    (crate::entropy() as u16) as f32 / u16::MAX as f32
+}
+
+fn parse_spec(spec: &str) -> Result<Vec<Action>, String> {
+    let spec = spec.trim();
+    if spec.is_empty() {
+        return Err("empty failpoint spec".to_string());
+    }
+    spec.split("->").map(parse_segment).collect()
+}
+
+fn parse_segment(segment: &str) -> Result<Action, String> {
+    let mut rest = segment.trim();
+
+    let probability = if let Some(pct_idx) = rest.find('%') {
+        let (pct, tail) = rest.split_at(pct_idx);
+        let pct: f32 = pct
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid probability in failpoint segment {:?}", segment))?;
+        rest = tail[1..].trim();
+        pct / 100.0
+    } else {
+        1.0
+    };
+
+    let (action_part, count) = if let Some(star_idx) = rest.find('*') {
+        let (action_part, tail) = rest.split_at(star_idx);
+        let count: u64 = tail[1..]
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid count in failpoint segment {:?}", segment))?;
+        (action_part.trim(), Some(count))
+    } else {
+        (rest, None)
+    };
+
+    let kind = parse_kind(action_part)?;
+
+    Ok(Action {
+        probability,
+        kind,
+        count,
+    })
+}
+
+fn parse_kind(s: &str) -> Result<ActionKind, String> {
+    let s = s.trim();
+    if s == "off" {
+        Ok(ActionKind::Off)
+    } else if s == "panic" {
+        Ok(ActionKind::Panic)
+    } else if s == "print" {
+        Ok(ActionKind::Print)
+    } else if let Some(inner) = s.strip_prefix("delay(").and_then(|s| s.strip_suffix(')')) {
+        let ms: u64 = inner
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid delay duration in failpoint action {:?}", s))?;
+        Ok(ActionKind::Delay(ms))
+    } else if let Some(rest) = s.strip_prefix("return") {
+        Ok(ActionKind::Return(ReturnSpec {
+            kind: parse_optional_error_kind(rest)?,
+            severity: Severity::Retryable,
+        }))
+    } else if let Some(rest) = s.strip_prefix("fatal") {
+        Ok(ActionKind::Return(ReturnSpec {
+            kind: parse_optional_error_kind(rest)?,
+            severity: Severity::Fatal,
+        }))
+    } else {
+        Err(format!("unrecognized failpoint action {:?}", s))
+    }
+}
+
+/// Parses the optional `"(error_kind)"` suffix following a `return`/`fatal`
+/// action name, defaulting to `ErrorKind::Other` when absent.
+fn parse_optional_error_kind(rest: &str) -> Result<ErrorKind, String> {
+    let rest = rest.trim();
+    if rest.is_empty() {
+        return Ok(ErrorKind::Other);
+    }
+
+    let inner = rest
+        .strip_prefix('(')
+        .and_then(|s| s.strip_suffix(')'))
+        .ok_or_else(|| format!("expected \"(error_kind)\" after action, got {:?}", rest))?;
+
+    parse_error_kind(inner.trim())
+}
+
+fn parse_error_kind(s: &str) -> Result<ErrorKind, String> {
+    match s {
+        "other" => Ok(ErrorKind::Other),
+        "interrupted" => Ok(ErrorKind::Interrupted),
+        "would_block" => Ok(ErrorKind::WouldBlock),
+        "permission_denied" => Ok(ErrorKind::PermissionDenied),
+        "timed_out" => Ok(ErrorKind::TimedOut),
+        "unexpected_eof" => Ok(ErrorKind::UnexpectedEof),
+        other => Err(format!("unrecognized error kind {:?}", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_simple_action() {
+        let actions = parse_spec("return").unwrap();
+        assert_eq!(
+            actions,
+            vec![Action {
+                probability: 1.0,
+                kind: ActionKind::Return(ReturnSpec {
+                    kind: ErrorKind::Other,
+                    severity: Severity::Retryable,
+                }),
+                count: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_return_with_error_kind() {
+        let actions = parse_spec("return(would_block)").unwrap();
+        assert_eq!(
+            actions,
+            vec![Action {
+                probability: 1.0,
+                kind: ActionKind::Return(ReturnSpec {
+                    kind: ErrorKind::WouldBlock,
+                    severity: Severity::Retryable,
+                }),
+                count: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_fatal_with_error_kind() {
+        let actions = parse_spec("fatal(permission_denied)").unwrap();
+        assert_eq!(
+            actions,
+            vec![Action {
+                probability: 1.0,
+                kind: ActionKind::Return(ReturnSpec {
+                    kind: ErrorKind::PermissionDenied,
+                    severity: Severity::Fatal,
+                }),
+                count: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_chained_probabilistic_spec() {
+        let actions = parse_spec("50%return->off").unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                Action {
+                    probability: 0.5,
+                    kind: ActionKind::Return(ReturnSpec {
+                        kind: ErrorKind::Other,
+                        severity: Severity::Retryable,
+                    }),
+                    count: None,
+                },
+                Action {
+                    probability: 1.0,
+                    kind: ActionKind::Off,
+                    count: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_delay_with_count() {
+        let actions = parse_spec("delay(100)*3->off").unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                Action {
+                    probability: 1.0,
+                    kind: ActionKind::Delay(100),
+                    count: Some(3),
+                },
+                Action {
+                    probability: 1.0,
+                    kind: ActionKind::Off,
+                    count: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_action() {
+        assert!(parse_spec("nonsense").is_err());
+    }
+
+    #[test]
+    fn configured_failpoint_returns_until_count_exhausted_then_falls_back_to_chain() {
+        configure("failpoint::test::chain", "return*2->off").unwrap();
+
+        assert_eq!(
+            evaluate("failpoint::test::chain"),
+            Some(Outcome::Return(ErrorKind::Other))
+        );
+        assert_eq!(
+            evaluate("failpoint::test::chain"),
+            Some(Outcome::Return(ErrorKind::Other))
+        );
+        assert_eq!(evaluate("failpoint::test::chain"), Some(Outcome::Pass));
+
+        clear("failpoint::test::chain");
+        assert_eq!(evaluate("failpoint::test::chain"), None);
+    }
+
+    #[test]
+    fn fatal_action_fires_exactly_once_despite_higher_count() {
+        configure("failpoint::test::fatal", "fatal*5->off").unwrap();
+
+        assert_eq!(
+            evaluate("failpoint::test::fatal"),
+            Some(Outcome::Return(ErrorKind::Other))
+        );
+        assert_eq!(evaluate("failpoint::test::fatal"), Some(Outcome::Pass));
+
+        clear("failpoint::test::fatal");
+    }
+
+    #[test]
+    fn fifty_percent_action_fires_roughly_half_the_time() {
+        // Regression test: `random_unit` used to derive its probability
+        // from the unmixed low bits of `entropy`, which were biased, so a
+        // configured "50%return" fired at ~35% instead of ~50%.
+        configure("failpoint::test::fifty_percent", "50%return").unwrap();
+
+        let trials = 10_000;
+        let fires = (0..trials)
+            .filter(|_| evaluate("failpoint::test::fifty_percent") != Some(Outcome::Pass))
+            .count();
+        let rate = fires as f64 / trials as f64;
+
+        clear("failpoint::test::fifty_percent");
+
+        assert!(
+            (0.3..0.7).contains(&rate),
+            "50% action fired {rate:.3} of the time, expected roughly 0.5"
+        );
+    }
+}
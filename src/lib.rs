@@ -1,3 +1,8 @@
+pub mod failpoint;
+mod scope;
+
+pub use scope::FaultScope;
+
 /// Facilitates fault injection. Every time any IO operation
 /// is performed, this is decremented. If it hits 0, an
 /// io::Error is returned from that IO operation. Use this
@@ -10,6 +15,11 @@
 /// times per second for 100 years. If you're building something
 /// like that, maybe consider re-setting this to `u64::MAX` every
 /// few decades for safety.
+///
+/// This is only consulted by [`maybe!`]/[`fallible!`] call sites that
+/// either have no label, or whose label has not been registered with
+/// [`failpoint::configure`]. It is the fallback for the more targeted
+/// named-failpoint system in the [`failpoint`] module.
 pub static FAULT_INJECT_COUNTER: core::sync::atomic::AtomicU64 =
     core::sync::atomic::AtomicU64::new(u64::MAX);
 
@@ -20,9 +30,209 @@ pub static FAULT_INJECT_COUNTER: core::sync::atomic::AtomicU64 =
 /// to play with the number sometimes for specific concurrent systems under test.
 pub static SLEEPINESS: core::sync::atomic::AtomicU32 = core::sync::atomic::AtomicU32::new(0);
 
+/// Facilitates probabilistic "one-in-N" fault injection, similar to
+/// RocksDB's `read_fault_one_in`. When nonzero, [`maybe!`] additionally
+/// injects an error with probability `1 / FAULT_INJECT_ONE_IN` on every
+/// call that takes the aggregate fallback path (no label, or a label not
+/// registered with [`failpoint::configure`]), independently of
+/// [`FAULT_INJECT_COUNTER`]. The [`Op`]-qualified form and configured
+/// labels do not consult it. Unlike the countdown
+/// counter, this never needs to be reset, which makes it a better fit for
+/// long-running stress tests that want to keep exercising error paths for
+/// as long as they run rather than tripping exactly once. Defaults to `0`
+/// (disabled).
+///
+/// [`FAULT_INJECT_COUNTER`]: FAULT_INJECT_COUNTER
+pub static FAULT_INJECT_ONE_IN: core::sync::atomic::AtomicU64 =
+    core::sync::atomic::AtomicU64::new(0);
+
 #[doc(hidden)]
 pub type Trigger = fn(&'static str, &'static str, u32);
 
+/// A coarse operation category for the per-path counters in
+/// [`FAULT_INJECT_COUNTERS`], mirroring the read/write/open split that
+/// RocksDB's `db_stress` draws with `open_read_fault_one_in` /
+/// `open_write_fault_one_in`. Pass one as the second argument to
+/// [`maybe!`]/[`fallible!`], e.g. `maybe!(expr, Op::Write)`, to decrement
+/// that category's counter instead of the aggregate
+/// [`FAULT_INJECT_COUNTER`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// A read-path operation, e.g. `read`/`pread`.
+    Read,
+    /// A write-path operation, e.g. `write`/`fsync`.
+    Write,
+    /// Opening or reopening a file, e.g. during startup/recovery.
+    Open,
+}
+
+impl Op {
+    fn index(self) -> usize {
+        match self {
+            Op::Read => 0,
+            Op::Write => 1,
+            Op::Open => 2,
+        }
+    }
+}
+
+const OP_COUNT: usize = 3;
+
+/// Per-[`Op`]-category counterparts to [`FAULT_INJECT_COUNTER`], so a test
+/// can exhaust write-path error handling while leaving reads untouched, or
+/// specifically stress the open/recovery phase without perturbing
+/// steady-state reads and writes. Only consulted by the
+/// `maybe!(expr, Op::Write)`/`fallible!(expr, Op::Write)` form; the
+/// label-less form and labels not registered with
+/// [`failpoint::configure`] are unaffected and keep decrementing the
+/// aggregate [`FAULT_INJECT_COUNTER`] for source compatibility. Each entry
+/// defaults to `u64::MAX`, same as the aggregate counter.
+///
+/// [`FAULT_INJECT_COUNTER`]: FAULT_INJECT_COUNTER
+pub static FAULT_INJECT_COUNTERS: [core::sync::atomic::AtomicU64; OP_COUNT] = [
+    core::sync::atomic::AtomicU64::new(u64::MAX),
+    core::sync::atomic::AtomicU64::new(u64::MAX),
+    core::sync::atomic::AtomicU64::new(u64::MAX),
+];
+
+/// Selects the `io::ErrorKind` used for errors injected by the aggregate
+/// fallback path ([`FAULT_INJECT_COUNTER`]/[`FAULT_INJECT_ONE_IN`]), encoded
+/// as a small fixed enumeration since `ErrorKind` itself has no atomic form.
+/// Defaults to `0`, i.e. [`ErrorKind::Other`](std::io::ErrorKind::Other). Set
+/// it with [`set_injected_error_kind`] so that code which branches on
+/// `ErrorKind` (retrying on `Interrupted`/`WouldBlock`, giving up on
+/// `PermissionDenied`) can be exercised deliberately.
+///
+/// Named failpoints configured via [`failpoint::configure`] have their own
+/// per-action `ErrorKind`, independent of this global.
+pub static FAULT_INJECT_ERROR_KIND: core::sync::atomic::AtomicU8 =
+    core::sync::atomic::AtomicU8::new(0);
+
+fn encode_error_kind(kind: std::io::ErrorKind) -> u8 {
+    use std::io::ErrorKind::*;
+    match kind {
+        Interrupted => 1,
+        WouldBlock => 2,
+        PermissionDenied => 3,
+        TimedOut => 4,
+        UnexpectedEof => 5,
+        _ => 0,
+    }
+}
+
+fn decode_error_kind(code: u8) -> std::io::ErrorKind {
+    use std::io::ErrorKind::*;
+    match code {
+        1 => Interrupted,
+        2 => WouldBlock,
+        3 => PermissionDenied,
+        4 => TimedOut,
+        5 => UnexpectedEof,
+        _ => Other,
+    }
+}
+
+/// Sets the `io::ErrorKind` used by the aggregate fallback path going
+/// forward. Unrecognized kinds are stored as
+/// [`ErrorKind::Other`](std::io::ErrorKind::Other); see
+/// [`FAULT_INJECT_ERROR_KIND`] for the fixed set of supported kinds.
+pub fn set_injected_error_kind(kind: std::io::ErrorKind) {
+    FAULT_INJECT_ERROR_KIND.store(
+        encode_error_kind(kind),
+        core::sync::atomic::Ordering::Release,
+    );
+}
+
+/// When nonzero, replaces the weakly pseudorandom `rdtsc`-derived
+/// [`entropy`] with a deterministic per-thread `SplitMix64` stream seeded
+/// from this value, so that on any single thread, the exact sequence of
+/// [`SLEEPINESS`]-driven `yield_now()` bursts becomes a pure function of
+/// the seed instead of real-time jitter. This is loom-style
+/// reproducibility: once a seed is found that triggers a concurrency bug
+/// on a single thread, re-running with the same seed replays that
+/// thread's schedule exactly. Set it with [`set_schedule_seed`]. Defaults
+/// to `0` (disabled, i.e. real entropy is used).
+///
+/// Note that this does not pin the *interleaving* across threads: each
+/// thread's stream is seeded in part by a first-touch ordinal handed out
+/// by a global counter, so two threads racing to first call [`entropy`]
+/// may be assigned different ordinals (and therefore different streams)
+/// on different runs. A reproduced multi-threaded race still needs the
+/// same thread-to-ordinal assignment to replay identically; a
+/// single-threaded schedule always replays.
+///
+/// [`SLEEPINESS`]: SLEEPINESS
+/// [`entropy`]: entropy
+/// [`set_schedule_seed`]: set_schedule_seed
+pub static SCHEDULER_SEED: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
+
+/// Enables deterministic delay scheduling, seeded with `seed`. See
+/// [`SCHEDULER_SEED`] for details. Pass `0` to disable and go back to real
+/// entropy.
+///
+/// Calling this again, even with the same `seed`, restarts every thread's
+/// `SplitMix64` stream from the beginning, so re-running a test with the
+/// same seed replays the same schedule rather than continuing whatever
+/// stream state a prior run left behind.
+pub fn set_schedule_seed(seed: u64) {
+    SCHEDULER_SEED.store(seed, core::sync::atomic::Ordering::Release);
+    SCHEDULE_GENERATION.fetch_add(1, core::sync::atomic::Ordering::AcqRel);
+}
+
+/// Returns the current per-thread deterministic scheduler PRNG state, or
+/// `None` if [`SCHEDULER_SEED`] is unset. A [`set_trigger_function`]
+/// callback can call this when a fault fires to log exactly where in the
+/// stream it happened, so that a failing run can be captured and replayed
+/// by feeding the same [`SCHEDULER_SEED`] back in.
+pub fn schedule_state() -> Option<u64> {
+    if SCHEDULER_SEED.load(core::sync::atomic::Ordering::Acquire) == 0 {
+        return None;
+    }
+    Some(SCHEDULE_STATE.with(|cell| cell.get().2))
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let x = x.wrapping_add(0x9E3779B97F4A7C15);
+    let z = (x ^ (x >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    let z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+static THREAD_ORDINAL_SEQUENCE: core::sync::atomic::AtomicU64 =
+    core::sync::atomic::AtomicU64::new(0);
+
+/// Bumped on every [`set_schedule_seed`] call, including re-installing the
+/// same seed value, so [`deterministic_entropy`] can tell "still the run
+/// that seeded this thread's stream" apart from "a fresh run that happens
+/// to reuse the same seed" and restart the stream accordingly.
+static SCHEDULE_GENERATION: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
+
+thread_local! {
+    // (seed used to derive `state`, generation used to derive `state`,
+    // current stream state). `seed == 0` is the sentinel for "not yet
+    // seeded".
+    static SCHEDULE_STATE: core::cell::Cell<(u64, u64, u64)> =
+        const { core::cell::Cell::new((0, 0, 0)) };
+    static THREAD_ORDINAL: u64 =
+        THREAD_ORDINAL_SEQUENCE.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+}
+
+fn deterministic_entropy(seed: u64) -> u64 {
+    let generation = SCHEDULE_GENERATION.load(core::sync::atomic::Ordering::Acquire);
+    SCHEDULE_STATE.with(|cell| {
+        let (seed_used, generation_used, state) = cell.get();
+        let state = if seed_used == seed && generation_used == generation {
+            state
+        } else {
+            let ordinal = THREAD_ORDINAL.with(|o| *o);
+            splitmix64(seed.wrapping_add(ordinal.wrapping_mul(0x9E3779B97F4A7C15)))
+        };
+        let next = splitmix64(state);
+        cell.set((seed, generation, next));
+        next
+    })
+}
+
 /// This function will be called any time the [`FAULT_INJECT_COUNTER`] reaches 0
 /// and an error is injected. You can use this to re-set the counter for deep
 /// fault tree enumeration, test auditing, etc...
@@ -33,12 +243,184 @@ pub type Trigger = fn(&'static str, &'static str, u32);
 pub fn set_trigger_function(
     f: fn(crate_name: &'static str, file_name: &'static str, line_number: u32),
 ) {
-    TRIGGER_FN.store(f as usize as _, core::sync::atomic::Ordering::Release);
+    TRIGGER_FN.store(f as usize, core::sync::atomic::Ordering::Release);
+}
+
+#[doc(hidden)]
+pub static TRIGGER_FN: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+thread_local! {
+    static XORSHIFT_STATE: core::cell::Cell<u64> = core::cell::Cell::new(thread_xorshift_seed());
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn thread_xorshift_seed() -> u64 {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    std::thread::current().id().hash(&mut hasher);
+    match hasher.finish() {
+        0 => 0xdead_beef_cafe_babe,
+        seed => seed,
+    }
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn xorshift_next() -> u64 {
+    XORSHIFT_STATE.with(|cell| {
+        let mut x = cell.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        cell.set(x);
+        x
+    })
+}
+
+/// A source of entropy for delay and probabilistic fault injection. If
+/// [`SCHEDULER_SEED`] is set, this is a deterministic per-thread stream
+/// derived from that seed, for reproducible scheduling. Otherwise, it's
+/// weakly pseudorandom: on `x86`/`x86_64` it's derived from the `rdtsc`
+/// cycle counter, run through `SplitMix64` to whiten the low bits (the raw
+/// counter's low bits are not uniform, which would otherwise bias
+/// probability decisions like [`one_in`] and the failpoint probability
+/// gate that are derived from it); this is cheap and varies run-to-run
+/// without needing any state. On other architectures, where `rdtsc` isn't
+/// available, it falls back to a small per-thread xorshift stream seeded
+/// from the thread's id.
+///
+/// [`SCHEDULER_SEED`]: SCHEDULER_SEED
+#[doc(hidden)]
+pub fn entropy() -> u64 {
+    let scheduler_seed = SCHEDULER_SEED.load(core::sync::atomic::Ordering::Acquire);
+    if scheduler_seed != 0 {
+        return deterministic_entropy(scheduler_seed);
+    }
+
+    #[cfg(target_arch = "x86")]
+    {
+        splitmix64(unsafe { core::arch::x86::_rdtsc() })
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        splitmix64(unsafe { core::arch::x86_64::_rdtsc() })
+    }
+
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    {
+        xorshift_next()
+    }
+}
+
+#[doc(hidden)]
+pub fn inject_sleep() {
+    let sleepiness = scope::scoped_sleepiness()
+        .unwrap_or_else(|| SLEEPINESS.load(core::sync::atomic::Ordering::Acquire));
+    if sleepiness > 0 {
+        let random_sleeps = (entropy() as u16).trailing_zeros() * sleepiness;
+
+        for _ in 0..random_sleeps {
+            std::thread::yield_now();
+        }
+    }
+}
+
+/// Returns `true` with probability `1 / n` (and always `false` for `n == 0`),
+/// driven by [`entropy`].
+#[doc(hidden)]
+pub fn one_in(n: u64) -> bool {
+    n != 0 && entropy().is_multiple_of(n)
+}
+
+/// Decrements [`FAULT_INJECT_COUNTER`] and checks [`FAULT_INJECT_ONE_IN`],
+/// returning `true` if either says to inject a fault. This backs the
+/// aggregate fallback path used by [`maybe!`]/[`fallible!`] when no label,
+/// or an unregistered label, is given.
+///
+/// If a [`FaultScope`] is active on the current thread, its thread-local
+/// countdown is consulted instead of [`FAULT_INJECT_COUNTER`], so that
+/// scoped and global fault schedules never interfere with each other.
+#[doc(hidden)]
+pub fn aggregate_fault() -> bool {
+    let countdown_hit = scope::scoped_countdown_hit().unwrap_or_else(|| {
+        FAULT_INJECT_COUNTER.fetch_sub(1, core::sync::atomic::Ordering::AcqRel) == 1
+    });
+
+    let one_in_n = FAULT_INJECT_ONE_IN.load(core::sync::atomic::Ordering::Acquire);
+
+    countdown_hit || one_in(one_in_n)
 }
 
+/// Decrements the [`FAULT_INJECT_COUNTERS`] entry for `op`, returning `true`
+/// if it just hit 0. This backs the `Op`-qualified form of
+/// [`maybe!`]/[`fallible!`], analogous to how [`aggregate_fault`] backs the
+/// label-less aggregate fallback path.
 #[doc(hidden)]
-pub static TRIGGER_FN: core::sync::atomic::AtomicPtr<Trigger> =
-    core::sync::atomic::AtomicPtr::new(0 as usize as _);
+pub fn category_fault(op: Op) -> bool {
+    FAULT_INJECT_COUNTERS[op.index()].fetch_sub(1, core::sync::atomic::Ordering::AcqRel) == 1
+}
+
+#[doc(hidden)]
+pub fn fire_trigger(crate_name: &'static str, file_name: &'static str, line_number: u32) {
+    if let Some(f) = scope::scoped_trigger() {
+        f(crate_name, file_name, line_number);
+        return;
+    }
+
+    let trigger_fn = TRIGGER_FN.load(core::sync::atomic::Ordering::Acquire);
+    if trigger_fn != 0 {
+        unsafe {
+            let f: Trigger = std::mem::transmute::<usize, Trigger>(trigger_fn);
+            (f)(crate_name, file_name, line_number);
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn injected_error(
+    crate_name: &'static str,
+    file_name: &'static str,
+    line_number: u32,
+) -> std::io::Error {
+    let kind =
+        decode_error_kind(FAULT_INJECT_ERROR_KIND.load(core::sync::atomic::Ordering::Acquire));
+    injected_error_with_kind(kind, crate_name, file_name, line_number)
+}
+
+#[doc(hidden)]
+pub fn injected_error_with_kind(
+    kind: std::io::ErrorKind,
+    crate_name: &'static str,
+    file_name: &'static str,
+    line_number: u32,
+) -> std::io::Error {
+    std::io::Error::new(
+        kind,
+        format!(
+            "injected fault at {}:{}:{}",
+            crate_name, file_name, line_number
+        ),
+    )
+}
+
+#[doc(hidden)]
+pub fn annotate<T>(
+    result: std::io::Result<T>,
+    crate_name: &'static str,
+    file_name: &'static str,
+    line_number: u32,
+) -> std::io::Result<T> {
+    // annotates io::Error to include the source of the error
+    match result {
+        Ok(ok) => Ok(ok),
+        Err(e) => Err(std::io::Error::new(
+            e.kind(),
+            format!("{}:{}:{} -> {}", crate_name, file_name, line_number, e),
+        )),
+    }
+}
 
 /// Similar to the `try!` macro or `?` operator,
 /// but externally controllable to inject faults
@@ -58,6 +440,48 @@ pub static TRIGGER_FN: core::sync::atomic::AtomicPtr<Trigger> =
 /// something other than 0, this macro will also
 /// inject weakly pseudorandom delays for
 /// facilitating a basic form of concurrency testing.
+/// Independently of the countdown, if [`FAULT_INJECT_ONE_IN`] is set to
+/// a nonzero `n`, each call also has roughly a `1 / n` chance of
+/// injecting an error, which is handy for long-running stress tests that
+/// should keep exercising error paths instead of tripping exactly once.
+/// A [`FaultScope`] active on the current thread takes precedence over
+/// [`FAULT_INJECT_COUNTER`] and [`SLEEPINESS`], so tests can run their own
+/// hermetic fault schedules in parallel without a global mutex.
+///
+/// The sleep delays above are ordinarily driven by weakly pseudorandom
+/// entropy, which makes a schedule that triggers a concurrency bug
+/// impossible to replay. Setting [`SCHEDULER_SEED`] switches to a
+/// deterministic per-thread stream instead, so each thread's own sequence
+/// of delays recurs every time the same seed is used; see
+/// [`SCHEDULER_SEED`] for the caveat on cross-thread interleaving.
+///
+/// An optional second argument gives the call site a static label, e.g.
+/// `fallible!(do_io(), "wal::fsync")`. Labeled call sites are looked up in
+/// the [`failpoint`] registry, letting a test target faults at one
+/// subsystem without touching every other `fallible!`/`maybe!` in the
+/// process. If the label has not been configured with
+/// [`failpoint::configure`], the call falls back to the aggregate
+/// [`FAULT_INJECT_COUNTER`] behavior described above.
+///
+/// Instead of a label, the second argument can be an [`Op`] variant, e.g.
+/// `fallible!(do_io(), Op::Write)`, to decrement that category's entry in
+/// [`FAULT_INJECT_COUNTERS`] rather than the aggregate
+/// [`FAULT_INJECT_COUNTER`]. This lets a test exhaust write-path error
+/// handling while leaving reads untouched, or drive faults only during the
+/// open/recovery phase, without a named failpoint at every call site.
+///
+/// Injected errors always carry an `ErrorKind`, which defaults to `Other`
+/// but can be set so that code paths which branch on it (retrying on
+/// `Interrupted`/`WouldBlock`, giving up on `PermissionDenied`) can be
+/// exercised deliberately. For the aggregate fallback path, set
+/// [`set_injected_error_kind`]; for a named failpoint, configure the kind
+/// directly on its `return`/`fatal` action, e.g. `"return(would_block)"`.
+/// A failpoint action's severity also matters here: a `return` action
+/// keeps injecting according to its configured count (soft/retryable,
+/// mirroring how [`FAULT_INJECT_ONE_IN`] keeps injecting indefinitely),
+/// while a `fatal` action always fires exactly once before the chain
+/// advances (mirroring how the [`FAULT_INJECT_COUNTER`] countdown trips
+/// only once per reset).
 ///
 /// # Examples
 /// ```
@@ -89,14 +513,53 @@ pub static TRIGGER_FN: core::sync::atomic::AtomicPtr<Trigger> =
 /// assert!(use_it().is_err());
 /// ```
 ///
+/// ```
+/// use std::io;
+///
+/// use fault_injection::{fallible, Op, FAULT_INJECT_COUNTERS};
+///
+/// fn do_read() -> io::Result<()> {
+///     Ok(())
+/// }
+///
+/// fn do_write() -> io::Result<()> {
+///     Ok(())
+/// }
+///
+/// // only the write path is made to fail; reads are untouched.
+/// FAULT_INJECT_COUNTERS[Op::Write as usize].store(1, std::sync::atomic::Ordering::Release);
+///
+/// fn use_it() -> io::Result<()> {
+///     fallible!(do_read(), Op::Read);
+///     fallible!(do_write(), Op::Write);
+///     Ok(())
+/// }
+///
+/// assert!(use_it().is_err());
+/// ```
+///
 ///
 /// [`FAULT_INJECT_COUNTER`]: FAULT_INJECT_COUNTER
+/// [`FAULT_INJECT_ONE_IN`]: FAULT_INJECT_ONE_IN
+/// [`FAULT_INJECT_COUNTERS`]: FAULT_INJECT_COUNTERS
+/// [`Op`]: Op
 /// [`SLEEPINESS`]: SLEEPINESS
+/// [`failpoint`]: failpoint
+/// [`failpoint::configure`]: failpoint::configure
+/// [`set_injected_error_kind`]: set_injected_error_kind
+/// [`FaultScope`]: FaultScope
+/// [`SCHEDULER_SEED`]: SCHEDULER_SEED
 #[macro_export]
 macro_rules! fallible {
     ($e:expr) => {{
         fault_injection::maybe!($e)?
     }};
+    ($e:expr, Op::$op:ident) => {{
+        fault_injection::maybe!($e, Op::$op)?
+    }};
+    ($e:expr, $label:expr) => {{
+        fault_injection::maybe!($e, $label)?
+    }};
 }
 
 /// Performs the same fault injection as [`fallible`] but does not
@@ -107,23 +570,28 @@ macro_rules! fallible {
 #[macro_export]
 macro_rules! maybe {
     ($e:expr) => {{
-        let sleepiness = fault_injection::SLEEPINESS.load(core::sync::atomic::Ordering::Acquire);
-        if sleepiness > 0 {
-            #[cfg(target_arch = "x86")]
-            let rdtsc = unsafe { core::arch::x86::_rdtsc() as u16 };
-
-            #[cfg(target_arch = "x86_64")]
-            let rdtsc = unsafe { core::arch::x86_64::_rdtsc() as u16 };
+        fault_injection::inject_sleep();
 
-            #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
-            let rdtsc = 0b10;
+        const CRATE_NAME: &str = if let Some(name) = core::option_env!("CARGO_CRATE_NAME") {
+            name
+        } else {
+            ""
+        };
 
-            let random_sleeps = rdtsc.trailing_zeros() as u32 * sleepiness;
+        if fault_injection::aggregate_fault() {
+            fault_injection::fire_trigger(CRATE_NAME, file!(), line!());
 
-            for _ in 0..random_sleeps {
-                std::thread::yield_now();
-            }
+            Err(fault_injection::injected_error(
+                CRATE_NAME,
+                file!(),
+                line!(),
+            ))
+        } else {
+            fault_injection::annotate($e, CRATE_NAME, file!(), line!())
         }
+    }};
+    ($e:expr, Op::$op:ident) => {{
+        fault_injection::inject_sleep();
 
         const CRATE_NAME: &str = if let Some(name) = core::option_env!("CARGO_CRATE_NAME") {
             name
@@ -131,38 +599,92 @@ macro_rules! maybe {
             ""
         };
 
-        if fault_injection::FAULT_INJECT_COUNTER.fetch_sub(1, core::sync::atomic::Ordering::AcqRel)
-            == 1
-        {
-            let trigger_fn = fault_injection::TRIGGER_FN.load(core::sync::atomic::Ordering::Acquire);
-            if !trigger_fn.is_null() {
-                unsafe {
-                    let f: fault_injection::Trigger = std::mem::transmute(trigger_fn);
-                    (f)(CRATE_NAME, file!(), line!());
-                }
-            }
+        if fault_injection::category_fault(fault_injection::Op::$op) {
+            fault_injection::fire_trigger(CRATE_NAME, file!(), line!());
 
-            Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("injected fault at {}:{}:{}", CRATE_NAME, file!(), line!()),
+            Err(fault_injection::injected_error(
+                CRATE_NAME,
+                file!(),
+                line!(),
             ))
         } else {
-            // annotates io::Error to include the source of the error
-            match $e {
-                Ok(ok) => Ok(ok),
-                Err(e) => {
-                    Err(std::io::Error::new(
-                        e.kind(),
-                        format!(
-                            "{}:{}:{} -> {}",
-                            CRATE_NAME,
-                            file!(),
-                            line!(),
-                            e.to_string()
-                        ),
+            fault_injection::annotate($e, CRATE_NAME, file!(), line!())
+        }
+    }};
+    ($e:expr, $label:expr) => {{
+        fault_injection::inject_sleep();
+
+        const CRATE_NAME: &str = if let Some(name) = core::option_env!("CARGO_CRATE_NAME") {
+            name
+        } else {
+            ""
+        };
+
+        match fault_injection::failpoint::evaluate($label) {
+            Some(fault_injection::failpoint::Outcome::Pass) => {
+                fault_injection::annotate($e, CRATE_NAME, file!(), line!())
+            }
+            Some(fault_injection::failpoint::Outcome::Return(kind)) => {
+                fault_injection::fire_trigger(CRATE_NAME, file!(), line!());
+
+                Err(fault_injection::injected_error_with_kind(
+                    kind,
+                    CRATE_NAME,
+                    file!(),
+                    line!(),
+                ))
+            }
+            Some(fault_injection::failpoint::Outcome::Delay(ms)) => {
+                std::thread::sleep(std::time::Duration::from_millis(ms));
+
+                fault_injection::annotate($e, CRATE_NAME, file!(), line!())
+            }
+            None => {
+                if fault_injection::aggregate_fault() {
+                    fault_injection::fire_trigger(CRATE_NAME, file!(), line!());
+
+                    Err(fault_injection::injected_error(
+                        CRATE_NAME,
+                        file!(),
+                        line!(),
                     ))
+                } else {
+                    fault_injection::annotate($e, CRATE_NAME, file!(), line!())
                 }
             }
         }
     }};
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scheduler_seed_makes_entropy_deterministic() {
+        set_schedule_seed(0xC0FFEE);
+        let first_run: Vec<u64> = (0..8).map(|_| entropy()).collect();
+
+        // Reseeding with the same value replays the same stream from the
+        // start.
+        set_schedule_seed(0xC0FFEE);
+        let second_run: Vec<u64> = (0..8).map(|_| entropy()).collect();
+
+        set_schedule_seed(0);
+
+        assert_eq!(first_run, second_run);
+    }
+
+    #[test]
+    fn one_in_is_roughly_uniform_in_default_mode() {
+        // Regression test for a bias in the unmixed rdtsc low bits: every
+        // sample used to come out odd, so `one_in(2)` never fired.
+        let trials = 10_000;
+        let hits = (0..trials).filter(|_| one_in(2)).count();
+        let rate = hits as f64 / trials as f64;
+        assert!(
+            (0.3..0.7).contains(&rate),
+            "one_in(2) fired {rate:.3} of the time, expected roughly 0.5"
+        );
+    }
+}
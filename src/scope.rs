@@ -0,0 +1,141 @@
+//! A thread-local override layer for fault injection.
+//!
+//! [`FAULT_INJECT_COUNTER`](crate::FAULT_INJECT_COUNTER) and
+//! [`SLEEPINESS`](crate::SLEEPINESS) are global, so two `#[test]` functions
+//! running concurrently under cargo's default parallel test harness
+//! inevitably clobber each other's fault schedules. [`FaultScope`] installs
+//! a per-thread counter/sleepiness/trigger that [`maybe!`](crate::maybe)
+//! consults before falling back to those globals, so each thread can run
+//! its own hermetic fault schedule without a global mutex serializing tests.
+
+use std::cell::Cell;
+
+use crate::Trigger;
+
+thread_local! {
+    static SCOPED_COUNTER: Cell<Option<u64>> = const { Cell::new(None) };
+    static SCOPED_SLEEPINESS: Cell<Option<u32>> = const { Cell::new(None) };
+    static SCOPED_TRIGGER: Cell<Option<Trigger>> = const { Cell::new(None) };
+}
+
+/// RAII guard that installs a thread-local fault schedule, restoring
+/// whatever was previously installed (if anything) when it's dropped.
+///
+/// ```
+/// use fault_injection::{maybe, FaultScope};
+///
+/// fn do_io() -> std::io::Result<()> {
+///     Ok(())
+/// }
+///
+/// let _scope = FaultScope::new(1).with_sleepiness(0);
+/// assert!(maybe!(do_io()).is_err());
+/// // the scope's schedule is torn down here, restoring any outer scope
+/// // (or the global statics, if there was none).
+/// ```
+pub struct FaultScope {
+    prev_counter: Option<u64>,
+    prev_sleepiness: Option<Option<u32>>,
+    prev_trigger: Option<Option<Trigger>>,
+}
+
+impl FaultScope {
+    /// Installs a thread-local countdown, shadowing
+    /// [`FAULT_INJECT_COUNTER`](crate::FAULT_INJECT_COUNTER) for the
+    /// current thread until the returned scope is dropped.
+    pub fn new(counter: u64) -> FaultScope {
+        let prev_counter = SCOPED_COUNTER.with(|c| c.replace(Some(counter)));
+        FaultScope {
+            prev_counter,
+            prev_sleepiness: None,
+            prev_trigger: None,
+        }
+    }
+
+    /// Additionally installs a thread-local sleepiness, shadowing
+    /// [`SLEEPINESS`](crate::SLEEPINESS) for the current thread.
+    pub fn with_sleepiness(mut self, sleepiness: u32) -> FaultScope {
+        let prev = SCOPED_SLEEPINESS.with(|c| c.replace(Some(sleepiness)));
+        self.prev_sleepiness = Some(prev);
+        self
+    }
+
+    /// Additionally installs a thread-local trigger function, shadowing
+    /// whatever was set via
+    /// [`set_trigger_function`](crate::set_trigger_function) for the
+    /// current thread.
+    pub fn with_trigger(mut self, trigger: Trigger) -> FaultScope {
+        let prev = SCOPED_TRIGGER.with(|c| c.replace(Some(trigger)));
+        self.prev_trigger = Some(prev);
+        self
+    }
+}
+
+impl Drop for FaultScope {
+    fn drop(&mut self) {
+        SCOPED_COUNTER.with(|c| c.set(self.prev_counter));
+        if let Some(prev) = self.prev_sleepiness {
+            SCOPED_SLEEPINESS.with(|c| c.set(prev));
+        }
+        if let Some(prev) = self.prev_trigger {
+            SCOPED_TRIGGER.with(|c| c.set(prev));
+        }
+    }
+}
+
+/// Decrements the thread-local scoped counter and reports whether it just
+/// hit `0`, or `None` if no [`FaultScope`] is active on this thread, in
+/// which case the caller should fall back to the global
+/// [`FAULT_INJECT_COUNTER`](crate::FAULT_INJECT_COUNTER).
+#[doc(hidden)]
+pub fn scoped_countdown_hit() -> Option<bool> {
+    SCOPED_COUNTER.with(|c| {
+        let current = c.get()?;
+        c.set(Some(current.wrapping_sub(1)));
+        Some(current == 1)
+    })
+}
+
+/// Returns the thread-local scoped sleepiness, or `None` if no
+/// [`FaultScope`] on this thread has set one.
+#[doc(hidden)]
+pub fn scoped_sleepiness() -> Option<u32> {
+    SCOPED_SLEEPINESS.with(|c| c.get())
+}
+
+/// Returns the thread-local scoped trigger function, or `None` if no
+/// [`FaultScope`] on this thread has set one.
+#[doc(hidden)]
+pub fn scoped_trigger() -> Option<Trigger> {
+    SCOPED_TRIGGER.with(|c| c.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scope_overrides_and_restores_counter() {
+        assert_eq!(scoped_countdown_hit(), None);
+
+        {
+            let _scope = FaultScope::new(2);
+            assert_eq!(scoped_countdown_hit(), Some(false));
+            assert_eq!(scoped_countdown_hit(), Some(true));
+        }
+
+        assert_eq!(scoped_countdown_hit(), None);
+    }
+
+    #[test]
+    fn nested_scopes_restore_outer_schedule() {
+        let outer = FaultScope::new(5).with_sleepiness(3);
+        {
+            let _inner = FaultScope::new(1).with_sleepiness(9);
+            assert_eq!(scoped_sleepiness(), Some(9));
+        }
+        assert_eq!(scoped_sleepiness(), Some(3));
+        drop(outer);
+        assert_eq!(scoped_sleepiness(), None);
+    }
+}